@@ -1,31 +1,142 @@
 use ark_serialize::*;
+use ethers::{
+    core::k256::ecdsa::SigningKey, prelude::*, providers::ProviderError,
+    utils::secret_key_to_address,
+};
 use generic_array::GenericArray;
 use jf_txn::{structs::Nullifier, TransactionNote};
 use jf_utils::tagged_blob;
+use lru::LruCache;
 use serde::{Deserialize, Serialize};
 use sha3::{Digest, Keccak256};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use zerok_lib::{
     commit::{Commitment, Committable, RawCommitmentBuilder},
     ledger::traits::*,
     ValidationError,
 };
 
-// In CAPE, we don't store a sparse local copy of the nullifiers set; instead we use the on-ledger
-// nullifier set whenever we need to look up a nullifier. This type is just a stub.
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
-pub struct CAPENullifierSet;
+// The number of nullifier lookups to keep memoized, so that repeated queries for nullifiers in
+// the same block (or across adjacent blocks) don't all round-trip to the CAPE contract.
+const NULLIFIER_CACHE_SIZE: usize = 4096;
+
+// The number of distinct block commitments to hold receipts for at once. Without a cap, a peer
+// could grow `CAPEValidator::pending_commitments` without bound by sending validly-signed
+// receipts for made-up commitments that will never reach quorum; the least recently used
+// commitment is evicted first once this is exceeded.
+const PENDING_COMMITMENTS_CACHE_SIZE: usize = 256;
+
+// In CAPE, we don't store a sparse local copy of the nullifiers set; the CAPE contract is the
+// source of truth for which nullifiers have been spent, so this type reads that authoritative
+// state directly via `eth_call` rather than trusting a third party's word on it. Results are
+// memoized in an LRU cache keyed by nullifier, since a nullifier's spent status never changes from
+// spent back to unspent.
+#[derive(Clone)]
+pub struct CAPENullifierSet {
+    client: Arc<Provider<Http>>,
+    contract: Address,
+    cache: Arc<Mutex<LruCache<Nullifier, bool>>>,
+}
+
+impl CAPENullifierSet {
+    pub fn new(client: Arc<Provider<Http>>, contract: Address) -> Self {
+        Self {
+            client,
+            contract,
+            cache: Arc::new(Mutex::new(LruCache::new(NULLIFIER_CACHE_SIZE))),
+        }
+    }
+
+    // Ask the CAPE contract whether `nullifier` has already been spent, consulting (and updating)
+    // the cache first. `multi_insert` is a sync API, but the relayer that drives it runs on a
+    // tokio runtime, so we can't just `futures::executor::block_on` the `eth_call` here -- that
+    // blocks the runtime thread out from under any other task it's scheduling. `block_in_place`
+    // marks this worker thread as blocking and moves its other tasks to other workers first.
+    // This requires a multi-threaded runtime; it panics if called from a current-thread one.
+    fn is_spent(&self, nullifier: &Nullifier) -> bool {
+        if let Some(spent) = self.cache.lock().unwrap().get(nullifier) {
+            return *spent;
+        }
+        match tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(self.call_nullifier_exists(nullifier))
+        }) {
+            Ok(spent) => {
+                // Only a "spent" answer is final; an unspent nullifier can be spent later.
+                if spent {
+                    self.cache.lock().unwrap().put(*nullifier, spent);
+                }
+                spent
+            }
+            // Fail closed: if the contract can't be reached, reject the block that's asking
+            // about this nullifier rather than crashing the validator or vouching for a
+            // nullifier we were never able to actually check. Don't cache the failure, since it
+            // may just be a transient RPC hiccup.
+            Err(_) => true,
+        }
+    }
+
+    // Call the CAPE contract's `nullifierExists(bytes32) returns (bool)` view function.
+    async fn call_nullifier_exists(&self, nullifier: &Nullifier) -> Result<bool, ProviderError> {
+        let mut calldata = ethers::utils::id("nullifierExists(bytes32)").to_vec();
+        calldata.extend_from_slice(&nullifier_to_bytes32(nullifier));
+        let tx = TransactionRequest::new().to(self.contract).data(calldata);
+        let result = self.client.call(&tx.into(), None).await?;
+        // A `bool` return value is ABI-encoded as a single 32-byte word, non-zero for `true`.
+        Ok(result.iter().any(|byte| *byte != 0))
+    }
+}
+
+// Encode a nullifier as the contract's `bytes32` argument: its `CanonicalSerialize` bytes,
+// zero-padded on the right to 32 bytes. This is not on any hot path.
+fn nullifier_to_bytes32(nullifier: &Nullifier) -> [u8; 32] {
+    let mut bytes = vec![];
+    nullifier
+        .serialize(&mut bytes)
+        .expect("failed to serialize nullifier");
+    let mut buf = [0u8; 32];
+    buf[..bytes.len()].copy_from_slice(&bytes);
+    buf
+}
 
 impl NullifierSet for CAPENullifierSet {
-    type Proof = ();
+    // The "proof" that a nullifier has (or has not) been spent is simply the nullifier itself:
+    // since we trust the contract's answer to `nullifierExists`, no separate authentication data
+    // is required. On a double-spend, we hand the conflicting nullifier back as the error so the
+    // caller can report exactly which one was already spent.
+    type Proof = Nullifier;
 
-    fn multi_insert(
-        &mut self,
-        _nullifiers: &[(Nullifier, Self::Proof)],
-    ) -> Result<(), Self::Proof> {
+    fn multi_insert(&mut self, nullifiers: &[(Nullifier, Self::Proof)]) -> Result<(), Self::Proof> {
+        // Reject a nullifier spent twice within the same batch before making any contract calls:
+        // neither copy is on chain yet, so the on-chain check alone wouldn't catch this.
+        if let Some(duplicate) = first_batch_duplicate(nullifiers) {
+            return Err(duplicate);
+        }
+        for (nullifier, _) in nullifiers {
+            if self.is_spent(nullifier) {
+                return Err(*nullifier);
+            }
+        }
+        for (nullifier, _) in nullifiers {
+            self.cache.lock().unwrap().put(*nullifier, true);
+        }
         Ok(())
     }
 }
 
+// Find the first nullifier in `nullifiers` that appears more than once, if any. Split out as a
+// pure function (no contract access needed) so the batch-duplicate check is easy to unit test on
+// its own.
+fn first_batch_duplicate(nullifiers: &[(Nullifier, Nullifier)]) -> Option<Nullifier> {
+    let mut seen = HashSet::new();
+    for (nullifier, _) in nullifiers {
+        if !seen.insert(*nullifier) {
+            return Some(*nullifier);
+        }
+    }
+    None
+}
+
 #[tagged_blob("TX")]
 #[derive(Clone, Debug, CanonicalSerialize, CanonicalDeserialize)]
 pub struct CAPETransaction(TransactionNote);
@@ -46,7 +157,7 @@ impl Transaction for CAPETransaction {
     type NullifierSet = CAPENullifierSet;
     type Hash = CAPETransactionHash;
 
-    fn new(note: TransactionNote, _proofs: Vec<()>) -> Self {
+    fn new(note: TransactionNote, _proofs: Vec<Nullifier>) -> Self {
         Self(note)
     }
 
@@ -54,11 +165,11 @@ impl Transaction for CAPETransaction {
         &self.0
     }
 
-    fn proofs(&self) -> Vec<()> {
-        // There are no nullifier proofs in CAPE. The validator contract stores the full nullifiers
-        // set on the blockchain and does not require authentication for spending new nullifiers.
-        // Thus, we just need to return a list of () of the appropriate length.
-        vec![(); self.0.nullifiers().len()]
+    fn proofs(&self) -> Vec<Nullifier> {
+        // There is no separate nullifier proof in CAPE: the validator checks spentness directly
+        // against the CAPE contract, so the "proof" accompanying each nullifier is just the
+        // nullifier itself.
+        self.0.nullifiers()
     }
 
     fn hash(&self) -> Self::Hash {
@@ -93,14 +204,109 @@ impl Block for CAPEBlock {
     }
 }
 
-// In CAPE, we don't do local lightweight validation to check the results of queries. We trust the
-// results of Ethereum query services, and our local validator stores just enough information to
-// satisfy the Validator interface required by the wallet.
-//
-// Note that this might change if we end up implementing a lightweight CAPE validator in Rust as
-// part of the relayer service. In that case, we may be able to reuse that lightweight validator
-// here in order to avoid trusting a query service.
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+// A federation member attesting to CAPE blocks, weighted by however much stake (or other notion
+// of trust) the deployment apportions to it.
+pub type ValidatorSet = HashMap<Address, u64>;
+
+// A single validator node's attestation that it has seen and validated a given block. Rather than
+// trusting one query service's word that a block was committed, a relayer collects one of these
+// per known validator and only proceeds once a quorum of them agree.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockCommitmentReceipt {
+    pub block_commitment: Commitment<CAPEBlock>,
+    // The signer's state commitment immediately before it expects `block_commitment` to be
+    // applied. Binding the signature to this, rather than to `block_commitment` alone, stops a
+    // receipt captured off the wire (receipts are gossiped, not secret) from being replayed to
+    // re-apply the same block once the validator's state has already moved past the point this
+    // receipt was signed for.
+    pub prev_state_commitment: [u8; 32],
+    pub validator: Address,
+    pub signature: Signature,
+}
+
+impl BlockCommitmentReceipt {
+    /// Sign `block_commitment` with `signing_key`, producing a receipt attesting that the
+    /// corresponding address has seen this block and expects it to apply on top of
+    /// `prev_state_commitment`.
+    pub fn sign(
+        block_commitment: Commitment<CAPEBlock>,
+        prev_state_commitment: [u8; 32],
+        signing_key: &SigningKey,
+    ) -> Self {
+        let digest = commitment_digest(&block_commitment, &prev_state_commitment);
+        let (signature, recovery_id) = signing_key
+            .sign_prehash_recoverable(&digest)
+            .expect("failed to sign block commitment");
+        let signature = Signature {
+            r: U256::from_big_endian(&signature.r().to_bytes()),
+            s: U256::from_big_endian(&signature.s().to_bytes()),
+            v: recovery_id.to_byte() as u64 + 27,
+        };
+        Self {
+            block_commitment,
+            prev_state_commitment,
+            validator: secret_key_to_address(signing_key),
+            signature,
+        }
+    }
+
+    // Recover the address that produced `signature` over this receipt's block commitment and
+    // previous state commitment.
+    fn recover(&self) -> Option<Address> {
+        let digest = commitment_digest(&self.block_commitment, &self.prev_state_commitment);
+        self.signature
+            .recover(RecoveryMessage::Hash(H256::from_slice(&digest)))
+            .ok()
+    }
+}
+
+// Hash a block commitment and the state it's expected to apply on top of down to the 32-byte
+// digest that gets signed: Keccak256 of the serialized block commitment followed by
+// `prev_state_commitment`.
+fn commitment_digest(
+    commitment: &Commitment<CAPEBlock>,
+    prev_state_commitment: &[u8; 32],
+) -> [u8; 32] {
+    let mut bytes = vec![];
+    commitment
+        .serialize(&mut bytes)
+        .expect("failed to serialize block commitment");
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(
+        &Keccak256::new()
+            .chain(&bytes)
+            .chain(prev_state_commitment)
+            .finalize(),
+    );
+    buf
+}
+
+// The receipts collected for one block, accumulated as validator nodes attest to it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AggregatedCommitments(Vec<BlockCommitmentReceipt>);
+
+impl AggregatedCommitments {
+    pub fn new() -> Self {
+        Self(vec![])
+    }
+
+    /// Record a receipt, verifying that its signature actually recovers to the address it claims
+    /// to be from. Returns `false` (and does not record the receipt) if the signature is invalid.
+    pub fn push(&mut self, receipt: BlockCommitmentReceipt) -> bool {
+        if receipt.recover() != Some(receipt.validator) {
+            return false;
+        }
+        self.0.push(receipt);
+        true
+    }
+}
+
+// This is our lightweight CAPE validator: it still takes its blocks from the Ethereum query
+// service, but it no longer trusts that service's word on nullifiers, or on whether a block was
+// committed at all. Instead it consults `nullifiers`, which reads
+// authoritative spentness straight from the CAPE contract, and `validate_and_apply` itself refuses
+// to fold in a block until `validators` holding a quorum of the total weight have attested to it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CAPEValidator {
     // The current timestamp. The only requirement is that this is a monotonically increasing value,
     // but in this implementation it tracks the number of blocks committed.
@@ -110,11 +316,35 @@ pub struct CAPEValidator {
     // Current state commitment. This is a commitment to every block which has been committed, as
     // well as to the initial (now, num_records) state for good measure.
     commitment: GenericArray<u8, <Keccak256 as Digest>::OutputSize>,
+    // On-chain-backed nullifier set, used to detect double-spends. Holds a live Ethereum client,
+    // so unlike the rest of this struct it is not persisted; it must be re-attached with
+    // `with_nullifiers` after deserializing a `CAPEValidator`.
+    #[serde(skip)]
+    nullifiers: Option<CAPENullifierSet>,
+    // Known validator nodes and the weight (e.g. stake) backing each one's attestations.
+    validators: ValidatorSet,
+    // Receipts collected so far for blocks that haven't been applied yet, keyed by block
+    // commitment. `validate_and_apply` consults this -- and only this -- to decide whether a
+    // block has reached quorum; there is no way to apply a block without first feeding its
+    // receipts in here via `receive_commitment_receipt`. Bounded to
+    // `PENDING_COMMITMENTS_CACHE_SIZE` commitments (least recently used evicted first), since
+    // nothing else limits how many distinct commitments a peer can submit receipts for.
+    #[serde(skip, default = "default_pending_commitments")]
+    pending_commitments: LruCache<Commitment<CAPEBlock>, AggregatedCommitments>,
+}
+
+fn default_pending_commitments() -> LruCache<Commitment<CAPEBlock>, AggregatedCommitments> {
+    LruCache::new(PENDING_COMMITMENTS_CACHE_SIZE)
 }
 
 impl CAPEValidator {
     #[allow(dead_code)]
-    fn new(now: u64, num_records: u64) -> Self {
+    fn new(
+        now: u64,
+        num_records: u64,
+        nullifiers: CAPENullifierSet,
+        validators: ValidatorSet,
+    ) -> Self {
         Self {
             now,
             num_records,
@@ -123,9 +353,89 @@ impl CAPEValidator {
                 .chain(now.to_le_bytes())
                 .chain(num_records.to_le_bytes())
                 .finalize(),
+            nullifiers: Some(nullifiers),
+            validators,
+            pending_commitments: LruCache::new(PENDING_COMMITMENTS_CACHE_SIZE),
         }
     }
+
+    /// Re-attach a nullifier set after deserializing a `CAPEValidator`, whose live contract client
+    /// is not persisted.
+    #[allow(dead_code)]
+    fn with_nullifiers(mut self, nullifiers: CAPENullifierSet) -> Self {
+        self.nullifiers = Some(nullifiers);
+        self
+    }
+
+    /// Record a validator's attestation that it has seen and validated a block. This is the only
+    /// way a block's attested weight can grow; a relayer must call this for each receipt it
+    /// collects before `validate_and_apply` will accept the corresponding block. Returns `false`
+    /// if the receipt's signature doesn't match the address it claims to be from.
+    #[allow(dead_code)]
+    pub fn receive_commitment_receipt(&mut self, receipt: BlockCommitmentReceipt) -> bool {
+        let commitment = receipt.block_commitment;
+        if let Some(existing) = self.pending_commitments.get_mut(&commitment) {
+            return existing.push(receipt);
+        }
+        let mut aggregated = AggregatedCommitments::new();
+        if !aggregated.push(receipt) {
+            return false;
+        }
+        self.pending_commitments.put(commitment, aggregated);
+        true
+    }
+
+    fn total_weight(&self) -> u64 {
+        self.validators.values().sum()
+    }
+
+    // The minimum total weight of valid, distinct-signer receipts required to accept a block:
+    // strictly more than 2/3 of the total validator weight.
+    fn quorum_weight(&self) -> u64 {
+        (self.total_weight() * 2) / 3 + 1
+    }
+
+    // The total weight of recognized validators that have attested to `commitment` as the next
+    // block to apply on top of our current state, counting each validator at most once. A receipt
+    // only counts if its `prev_state_commitment` matches our current commitment: once a block is
+    // applied the commitment moves on, so the very same (validly signed) receipts broadcast for it
+    // can no longer be replayed to satisfy quorum a second time. Receipts are signature-checked at
+    // `receive_commitment_receipt` time, so there's no need to re-verify them here.
+    fn attested_weight(&mut self, commitment: &Commitment<CAPEBlock>) -> u64 {
+        let current_state = state_commitment_bytes(&self.commitment);
+        let receipts = match self.pending_commitments.get(commitment) {
+            Some(receipts) => receipts,
+            None => return 0,
+        };
+        let mut signers = HashSet::new();
+        receipts
+            .0
+            .iter()
+            .filter(|receipt| receipt.prev_state_commitment == current_state)
+            .filter(|receipt| signers.insert(receipt.validator))
+            .filter_map(|receipt| self.validators.get(&receipt.validator))
+            .sum()
+    }
+}
+
+fn state_commitment_bytes(
+    commitment: &GenericArray<u8, <Keccak256 as Digest>::OutputSize>,
+) -> [u8; 32] {
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(commitment);
+    buf
+}
+
+// The live nullifier-set client isn't comparable (or persisted), so equality is defined over the
+// validator's actual state: the block-committed timestamp, record count, and commitment.
+impl PartialEq for CAPEValidator {
+    fn eq(&self, other: &Self) -> bool {
+        self.now == other.now
+            && self.num_records == other.num_records
+            && self.commitment == other.commitment
+    }
 }
+impl Eq for CAPEValidator {}
 
 impl Validator for CAPEValidator {
     type StateCommitment = GenericArray<u8, <Keccak256 as Digest>::OutputSize>;
@@ -140,9 +450,39 @@ impl Validator for CAPEValidator {
     }
 
     fn validate_and_apply(&mut self, block: Self::Block) -> Result<Vec<u64>, ValidationError> {
-        // We don't actually do validation here, since in this implementation we trust the query
-        // service to provide only valid blocks. Instead, just compute a new commitment (by chaining
-        // the new block onto the current commitment hash, with a domain separator tag).
+        // Refuse to apply the block at all until a quorum of validators has attested to it. This
+        // is the only place blocks get applied, so there's no path through this validator that
+        // skips the check.
+        let expected_commitment = block.commit();
+        let weight = self.attested_weight(&expected_commitment);
+        if weight < self.quorum_weight() {
+            return Err(ValidationError::Failed {
+                msg: format!(
+                    "only {} of {} required validator weight attested to this block",
+                    weight,
+                    self.quorum_weight()
+                ),
+            });
+        }
+
+        // Reject the block outright if any of its nullifiers have already been spent, either
+        // on-chain or earlier in this same block.
+        let nullifiers = self
+            .nullifiers
+            .as_mut()
+            .expect("CAPEValidator used without a nullifier set attached");
+        let spends = block
+            .0
+            .iter()
+            .flat_map(|txn| txn.0.nullifiers().into_iter().map(|n| (n, n)))
+            .collect::<Vec<_>>();
+        if let Err(nullifier) = nullifiers.multi_insert(&spends) {
+            return Err(ValidationError::NullifierAlreadyExists { nullifier });
+        }
+
+        // We otherwise trust the query service to provide only valid blocks. Just compute a new
+        // commitment (by chaining the new block onto the current commitment hash, with a domain
+        // separator tag).
         self.commitment = Keccak256::new()
             .chain("block".as_bytes())
             .chain(&self.commitment)
@@ -161,6 +501,7 @@ impl Validator for CAPEValidator {
             }
         }
         self.num_records = uid;
+        self.pending_commitments.pop(&expected_commitment);
 
         Ok(uids)
     }
@@ -171,4 +512,138 @@ pub struct CAPELedger;
 
 impl Ledger for CAPELedger {
     type Validator = CAPEValidator;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::test_rng;
+
+    #[test]
+    fn first_batch_duplicate_finds_a_nullifier_repeated_within_a_batch() {
+        let rng = &mut test_rng();
+        let a = Nullifier::random(rng);
+        let b = Nullifier::random(rng);
+
+        assert_eq!(first_batch_duplicate(&[(a, a), (b, b)]), None);
+        assert_eq!(first_batch_duplicate(&[(a, a), (b, b), (a, a)]), Some(a));
+    }
+
+    // `Provider::try_from` only parses the URL; it doesn't dial out, so this is safe to use in a
+    // validator that never actually needs to make an `eth_call` (these tests only ever apply empty
+    // blocks, which have no nullifiers to check).
+    fn test_validator(validators: ValidatorSet) -> CAPEValidator {
+        let client = Arc::new(Provider::<Http>::try_from("http://localhost:8545").unwrap());
+        let nullifiers = CAPENullifierSet::new(client, Address::zero());
+        CAPEValidator::new(0, 0, nullifiers, validators)
+    }
+
+    #[test]
+    fn receipt_sign_recover_round_trips() {
+        let rng = &mut test_rng();
+        let signing_key = SigningKey::random(rng);
+        let block = CAPEBlock::new(vec![]);
+        let prev_state_commitment = [7u8; 32];
+
+        let receipt =
+            BlockCommitmentReceipt::sign(block.commit(), prev_state_commitment, &signing_key);
+        assert_eq!(receipt.recover(), Some(receipt.validator));
+    }
+
+    #[test]
+    fn push_rejects_a_receipt_whose_claimed_signer_does_not_match_its_signature() {
+        let rng = &mut test_rng();
+        let signing_key = SigningKey::random(rng);
+        let other_key = SigningKey::random(rng);
+        let block = CAPEBlock::new(vec![]);
+
+        let mut receipt = BlockCommitmentReceipt::sign(block.commit(), [0u8; 32], &signing_key);
+        // Tamper with the claimed signer without re-signing, simulating either a forged receipt
+        // or one that was corrupted in transit.
+        receipt.validator = secret_key_to_address(&other_key);
+
+        let mut aggregated = AggregatedCommitments::new();
+        assert!(!aggregated.push(receipt));
+    }
+
+    #[test]
+    fn attested_weight_ignores_receipts_from_validators_outside_the_known_set() {
+        let rng = &mut test_rng();
+        let known_key = SigningKey::random(rng);
+        let unknown_key = SigningKey::random(rng);
+
+        let mut validators = ValidatorSet::new();
+        validators.insert(secret_key_to_address(&known_key), 1);
+
+        let mut validator = test_validator(validators);
+        let block = CAPEBlock::new(vec![]);
+        let commitment = block.commit();
+        let prev_state_commitment = state_commitment_bytes(&validator.commitment);
+
+        validator.receive_commitment_receipt(BlockCommitmentReceipt::sign(
+            commitment,
+            prev_state_commitment,
+            &unknown_key,
+        ));
+        assert_eq!(validator.attested_weight(&commitment), 0);
+    }
+
+    #[test]
+    fn validate_and_apply_rejects_blocks_below_quorum_and_accepts_at_quorum() {
+        let rng = &mut test_rng();
+        let key_a = SigningKey::random(rng);
+        let key_b = SigningKey::random(rng);
+
+        let mut validators = ValidatorSet::new();
+        validators.insert(secret_key_to_address(&key_a), 2);
+        validators.insert(secret_key_to_address(&key_b), 1);
+
+        let mut validator = test_validator(validators);
+        assert_eq!(validator.quorum_weight(), 3);
+
+        let block = CAPEBlock::new(vec![]);
+        let commitment = block.commit();
+        let prev_state_commitment = state_commitment_bytes(&validator.commitment);
+
+        // Only `key_a`'s weight (2) has attested: one short of the quorum of 3, so the block is
+        // rejected.
+        validator.receive_commitment_receipt(BlockCommitmentReceipt::sign(
+            commitment,
+            prev_state_commitment,
+            &key_a,
+        ));
+        assert!(validator.validate_and_apply(block.clone()).is_err());
+
+        // Once `key_b` also attests, the combined weight (3) reaches quorum and the block applies.
+        validator.receive_commitment_receipt(BlockCommitmentReceipt::sign(
+            commitment,
+            prev_state_commitment,
+            &key_b,
+        ));
+        assert!(validator.validate_and_apply(block).is_ok());
+    }
+
+    #[test]
+    fn validate_and_apply_rejects_replaying_receipts_from_before_the_block_was_applied() {
+        let rng = &mut test_rng();
+        let signing_key = SigningKey::random(rng);
+
+        let mut validators = ValidatorSet::new();
+        validators.insert(secret_key_to_address(&signing_key), 1);
+
+        let mut validator = test_validator(validators);
+        let block = CAPEBlock::new(vec![]);
+        let commitment = block.commit();
+        let prev_state_commitment = state_commitment_bytes(&validator.commitment);
+        let receipt = BlockCommitmentReceipt::sign(commitment, prev_state_commitment, &signing_key);
+
+        validator.receive_commitment_receipt(receipt.clone());
+        assert!(validator.validate_and_apply(block.clone()).is_ok());
+
+        // Re-broadcasting the exact same, validly-signed receipt after the block has already been
+        // applied must not let it be applied a second time: our state commitment has moved on, so
+        // this receipt's `prev_state_commitment` no longer matches it.
+        validator.receive_commitment_receipt(receipt);
+        assert!(validator.validate_and_apply(block).is_err());
+    }
+}
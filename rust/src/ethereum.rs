@@ -0,0 +1,207 @@
+use anyhow::{Context, Result};
+use ark_serialize::CanonicalDeserialize;
+use contracts_rust::ledger::CAPETransaction;
+use ethers::{
+    abi::{Abi, RawLog},
+    contract::EthLogDecode,
+    middleware::gas_oracle::{GasOracle, GasOracleMiddleware},
+    prelude::*,
+    signers::{HDPath, Ledger},
+};
+use jf_txn::TransactionNote;
+use std::{fs, path::Path, sync::Arc, time::Duration};
+use zerok_lib::ledger::traits::Transaction;
+
+abigen!(
+    CAPE,
+    "artifacts/contracts/CAPE.sol/CAPE/abi.json",
+    event_derives(serde::Deserialize, serde::Serialize)
+);
+
+// The local Hardhat/Ganache RPC endpoint used for development and testing.
+const RPC_URL: &str = "http://localhost:8545";
+
+// Hardhat's well-known first test account. Only ever funded on local/dev networks; never use this
+// key anywhere a real CAPE block gets submitted.
+const FUNDED_DEPLOYER_KEY: &str =
+    "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+/// A deployer/relayer client backed by an in-memory private key. Convenient for local development
+/// and tests, but the key lives in process memory for as long as the client does; production
+/// deployments should prefer [`get_ledger_deployer`].
+pub async fn get_funded_deployer() -> Result<Arc<SignerMiddleware<Provider<Http>, LocalWallet>>> {
+    let provider = Provider::<Http>::try_from(RPC_URL)?.interval(Duration::from_millis(10));
+    let chain_id = provider.get_chainid().await?.as_u64();
+    let wallet = FUNDED_DEPLOYER_KEY
+        .parse::<LocalWallet>()?
+        .with_chain_id(chain_id);
+    Ok(Arc::new(SignerMiddleware::new(provider, wallet)))
+}
+
+/// A deployer/relayer client backed by a Ledger Nano's Ethereum app, so the signing key never
+/// leaves the hardware device. `derivation_index` selects the account exposed by the standard
+/// `m/44'/60'/{index}'/0/0` ("Ledger Live") derivation path; signatures are EIP-155 chain-id-aware
+/// for whatever network `RPC_URL` points at.
+pub async fn get_ledger_deployer(
+    derivation_index: usize,
+) -> Result<Arc<SignerMiddleware<Provider<Http>, Ledger>>> {
+    let provider = Provider::<Http>::try_from(RPC_URL)?.interval(Duration::from_millis(10));
+    let chain_id = provider.get_chainid().await?.as_u64();
+    let ledger = Ledger::new(HDPath::LedgerLive(derivation_index), chain_id)
+        .await
+        .context("failed to connect to Ledger; is the Ethereum app open?")?;
+    Ok(Arc::new(SignerMiddleware::new(provider, ledger)))
+}
+
+/// Enumerate the addresses a connected Ledger exposes at derivation indices `0..count`, so an
+/// operator can pick which account to pass to [`get_ledger_deployer`].
+pub async fn list_ledger_addresses(count: usize) -> Result<Vec<Address>> {
+    let mut addresses = vec![];
+    for i in 0..count {
+        let ledger = Ledger::new(HDPath::LedgerLive(i), 1)
+            .await
+            .context("failed to connect to Ledger; is the Ethereum app open?")?;
+        addresses.push(ledger.address());
+    }
+    Ok(addresses)
+}
+
+/// A relayer client stacking ethers-rs's standard middlewares on top of whatever `signer` is
+/// given: a [`NonceManagerMiddleware`] so concurrent block submissions don't collide on nonce
+/// reuse, and a [`GasOracleMiddleware`] so gas pricing comes from `gas_oracle` instead of being
+/// hardcoded. EIP-1559 transactions are priced through the oracle's `estimate_eip1559_fees` and
+/// legacy ones through its `fetch`; an oracle such as `EthGasStation::new(None)` only quotes
+/// legacy gas prices. Works with either a local wallet or a Ledger signer, since both implement `Signer`.
+pub async fn get_relayer_client<S: Signer + 'static, G: GasOracle + 'static>(
+    signer: S,
+    gas_oracle: G,
+) -> Result<Arc<GasOracleMiddleware<NonceManagerMiddleware<SignerMiddleware<Provider<Http>, S>>, G>>>
+{
+    let provider = Provider::<Http>::try_from(RPC_URL)?.interval(Duration::from_millis(10));
+    let address = signer.address();
+    let client = SignerMiddleware::new(provider, signer).nonce_manager(address);
+    let client = GasOracleMiddleware::new(client, gas_oracle);
+    Ok(Arc::new(client))
+}
+
+/// Submit a CAPE block to `contract`'s `submitBlock`-style entry point. Generic over `Middleware`
+/// so a relayer can submit through [`get_relayer_client`]'s pipelined, dynamically-priced stack
+/// instead of a bare signer.
+pub async fn submit_cape_block<M: Middleware + 'static>(
+    client: Arc<M>,
+    contract: Address,
+    block_calldata: Bytes,
+) -> Result<TransactionReceipt> {
+    let tx = Eip1559TransactionRequest::new()
+        .to(contract)
+        .data(block_calldata);
+    client
+        .send_transaction(tx, None)
+        .await
+        .map_err(|err| anyhow::anyhow!("failed to submit CAPE block: {err}"))?
+        .await?
+        .context("CAPE block submission dropped from the mempool")
+}
+
+/// Deploy a contract from a Hardhat-style artifact directory (expected to contain `abi.json` and
+/// `bytecode.bin`), using whatever signer `client` was built with. Generic over `Middleware` so
+/// callers can deploy with either [`get_funded_deployer`] or [`get_ledger_deployer`] without this
+/// function caring which.
+pub async fn deploy<M: Middleware + 'static, T: Tokenize>(
+    client: Arc<M>,
+    artifact: &Path,
+    constructor_args: T,
+) -> Result<Contract<M>> {
+    let abi: Abi = serde_json::from_str(
+        &fs::read_to_string(artifact.join("abi.json"))
+            .with_context(|| format!("reading ABI for {}", artifact.display()))?,
+    )?;
+    let bytecode = Bytes::from(
+        fs::read(artifact.join("bytecode.bin"))
+            .with_context(|| format!("reading bytecode for {}", artifact.display()))?,
+    );
+    let factory = ContractFactory::new(abi, bytecode, client);
+    let contract = factory.deploy(constructor_args)?.send().await?;
+    Ok(contract)
+}
+
+/// Scan `[from_block, to_block]` for `contract`'s ERC-20 wrap/deposit events, translating each
+/// confirmed deposit into the `CAPETransaction` that `CAPEBlock::new` expects. A deposit is only
+/// confirmed if the same transaction also contains a real ERC-20 `Transfer` log paying the
+/// deposited amount into `contract`, so a spoofed deposit event with no backing transfer is
+/// ignored rather than turned into a transaction the local validator will apply.
+pub async fn scan_deposits<M: Middleware + 'static>(
+    client: Arc<M>,
+    contract: Address,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<CAPETransaction>> {
+    let filter = Filter::new()
+        .address(contract)
+        .event("Erc20TokensDeposited(address,address,uint256,bytes)")
+        .from_block(from_block)
+        .to_block(to_block);
+    let logs = client
+        .get_logs(&filter)
+        .await
+        .map_err(|err| anyhow::anyhow!("eth_getLogs failed: {err}"))?;
+
+    let mut txns = vec![];
+    for log in logs {
+        let tx_hash = log
+            .transaction_hash
+            .context("deposit log is missing its transaction hash")?;
+        let deposit = Erc20TokensDepositedFilter::decode_log(&RawLog {
+            topics: log.topics,
+            data: log.data.to_vec(),
+        })
+        .context("failed to decode Erc20TokensDeposited log")?;
+
+        if !transfer_into_contract_exists(
+            client.as_ref(),
+            tx_hash,
+            deposit.token,
+            contract,
+            deposit.amount,
+        )
+        .await?
+        {
+            // No matching ERC-20 Transfer in the same transaction: this event wasn't backed by a
+            // real transfer, so don't trust it.
+            continue;
+        }
+
+        let note = TransactionNote::deserialize(&*deposit.ro_bytes)
+            .context("deposit event contained a malformed transaction note")?;
+        txns.push(CAPETransaction::new(note, vec![]));
+    }
+    Ok(txns)
+}
+
+// Check whether `tx_hash` also emitted an ERC-20 `Transfer(address,address,uint256)` log, from
+// `token` itself, paying at least `amount` into `contract`. A transaction receipt includes logs
+// emitted by every contract the transaction touched, not just `token` -- without pinning the log's
+// emitting address down to `token`, an attacker could have some unrelated, attacker-controlled
+// contract emit a crafted `Transfer` log in the same transaction as a forged deposit event, with
+// no real token ever moving. Checking `log.address == token` is what actually makes this a
+// cross-check against `token`'s own log rather than just a shape match.
+async fn transfer_into_contract_exists<M: Middleware>(
+    client: &M,
+    tx_hash: H256,
+    token: Address,
+    contract: Address,
+    amount: U256,
+) -> Result<bool> {
+    let receipt = client
+        .get_transaction_receipt(tx_hash)
+        .await
+        .map_err(|err| anyhow::anyhow!("eth_getTransactionReceipt failed: {err}"))?
+        .context("deposit transaction not found")?;
+    let transfer_topic = H256::from(ethers::utils::keccak256("Transfer(address,address,uint256)"));
+    Ok(receipt.logs.iter().any(|log| {
+        log.address == token
+            && log.topics.first() == Some(&transfer_topic)
+            && log.topics.get(2) == Some(&H256::from(contract))
+            && U256::from_big_endian(&log.data) >= amount
+    }))
+}